@@ -0,0 +1,109 @@
+// Passphrase-protected private keys via gpg-agent.
+//
+// Secret key material embedded in a `Cert` that's passphrase-protected
+// can't be turned into a `KeyPair` directly - GnuPG normally unlocks it by
+// prompting the user's `pinentry`. For non-interactive CI use we install a
+// tiny loopback pinentry stub that answers with `INPUT_KEY_PASSPHRASE`,
+// wire it up via gpg-agent's `pinentry-program`/`allow-loopback-pinentry`
+// options, import the secret key material into the freshly-started
+// ephemeral agent, and then let Sequoia's own GnuPG IPC client
+// (`sequoia-gpg-agent`) do the PKDECRYPT handshake over the agent's Assuan
+// socket.
+
+use anyhow::{bail, Context, Result};
+use sequoia_gpg_agent::gnupg::Context as GnupgContext;
+use sequoia_gpg_agent::Agent;
+use sequoia_openpgp::cert::Cert;
+use sequoia_openpgp::crypto::Decryptor;
+use sequoia_openpgp::policy::StandardPolicy;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Writes a pinentry stub and `gpg-agent.conf` into `gpg_home` so PKDECRYPT
+/// requests are satisfied with `passphrase` instead of prompting a human.
+pub fn install_loopback_pinentry(gpg_home: &Path, passphrase: &str) -> Result<PathBuf> {
+    let script_path = gpg_home.join("pinentry-loopback.sh");
+    let script = format!(
+        "#!/bin/sh\n\
+         # Minimal Assuan pinentry stub for non-interactive CI use.\n\
+         echo 'OK Pleased to meet you'\n\
+         while IFS= read -r line; do\n\
+           case \"$line\" in\n\
+             GETPIN*) printf 'D %s\\n' {passphrase}; echo OK ;;\n\
+             BYE) echo OK; exit 0 ;;\n\
+             *) echo OK ;;\n\
+           esac\n\
+         done\n",
+        passphrase = shell_quote(passphrase),
+    );
+    fs::write(&script_path, script).context("failed to write pinentry stub")?;
+    fs::set_permissions(&script_path, fs::Permissions::from_mode(0o700))
+        .context("failed to make pinentry stub executable")?;
+
+    let conf_path = gpg_home.join("gpg-agent.conf");
+    fs::write(
+        &conf_path,
+        format!(
+            "pinentry-program {}\nallow-loopback-pinentry\n",
+            script_path.display()
+        ),
+    )
+    .context("failed to write gpg-agent.conf")?;
+
+    Ok(script_path)
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Imports every encryption-capable secret subkey of `cert` into the
+/// ephemeral agent running under `gpg_home`, then builds agent-backed
+/// decryptors for them so `pgp::decrypt_data_key` can use them exactly like
+/// a direct in-memory `KeyPair`.
+///
+/// The import is necessary because a brand-new ephemeral `gpg-agent` has
+/// never seen this key's keygrip before - without it, every PKDECRYPT
+/// request would fail with "no secret key".
+pub async fn agent_decryptors(
+    gpg_home: &Path,
+    cert: &Cert,
+) -> Result<Vec<Box<dyn Decryptor + Send + Sync>>> {
+    let policy = StandardPolicy::new();
+    let ctx = GnupgContext::with_homedir(gpg_home).context("failed to connect to gpg-agent")?;
+    let mut agent = Agent::connect(&ctx)
+        .await
+        .context("failed to connect to gpg-agent")?;
+
+    let mut decryptors: Vec<Box<dyn Decryptor + Send + Sync>> = Vec::new();
+    for ka in cert
+        .keys()
+        .with_policy(&policy, None)
+        .secret()
+        .for_transport_encryption()
+        .for_storage_encryption()
+    {
+        let secret_key = ka.key();
+        agent
+            .import(&policy, cert, secret_key, true, true)
+            .await
+            .with_context(|| {
+                format!("failed to import {} into gpg-agent", secret_key.fingerprint())
+            })?;
+
+        let keypair = agent.keypair(secret_key.parts_as_public()).with_context(|| {
+            format!("gpg-agent has no usable key for {}", secret_key.fingerprint())
+        })?;
+        decryptors.push(Box::new(keypair));
+    }
+
+    if decryptors.is_empty() {
+        bail!(
+            "cert {} has no usable encryption-capable subkey",
+            cert.fingerprint()
+        );
+    }
+
+    Ok(decryptors)
+}
@@ -0,0 +1,71 @@
+// Ephemeral working-directory support so this action never touches a
+// user's real `$HOME/.gnupg` and never leaves private key material
+// sitting around after a run.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// A scratch "GPG home" directory. In ephemeral mode (the default) this is
+/// backed by a `tempfile::TempDir`, which is recursively removed as soon as
+/// this value is dropped - including on early return from a failed
+/// re-encryption. With ephemeral mode disabled we fall back to whatever
+/// `GNUPGHOME` (or `$HOME/.gnupg`) the caller configured, and leave it
+/// untouched on drop.
+pub enum EphemeralHome {
+    Ephemeral(TempDir),
+    Persistent(PathBuf),
+}
+
+impl EphemeralHome {
+    pub fn new(ephemeral: bool) -> Result<Self> {
+        if ephemeral {
+            let dir = TempDir::new().context("failed to create ephemeral GPG home")?;
+            Ok(EphemeralHome::Ephemeral(dir))
+        } else {
+            let path = std::env::var("GNUPGHOME").unwrap_or_else(|_| {
+                format!(
+                    "{}/.gnupg",
+                    std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string())
+                )
+            });
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("failed to create GPG home {path}"))?;
+            Ok(EphemeralHome::Persistent(PathBuf::from(path)))
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            EphemeralHome::Ephemeral(dir) => dir.path(),
+            EphemeralHome::Persistent(path) => path.as_path(),
+        }
+    }
+}
+
+/// A secret buffer that is overwritten with zeroes as soon as it is
+/// dropped, so a decoded private key never lingers in memory (or a core
+/// dump) longer than the single `Cert::from_bytes` call that needs it.
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        SecretBytes(bytes)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        for byte in self.0.iter_mut() {
+            // SAFETY: a plain write here would be optimized away by the
+            // compiler; `write_volatile` forces it to actually happen.
+            unsafe {
+                std::ptr::write_volatile(byte, 0);
+            }
+        }
+    }
+}
@@ -6,21 +6,43 @@
 //   - INPUT_PUBLIC_KEYS: JSON array of users with GPG keys from get-users-with-access-on-repo
 //   - INPUT_FLUX_KEY: Flux GPG public key (base64-encoded)
 //   - INPUT_SECRETS_PATTERN: Glob pattern for secret files (default: **/application.secrets.env)
-//   - INPUT_SOPS_VERSION: SOPS version to use
+//   - INPUT_EPHEMERAL: Use a throwaway GPG home instead of $HOME/.gnupg (default: true)
+//   - INPUT_DRY_RUN: Report which files would change without writing anything (default: false)
+//   - INPUT_KEY_PASSPHRASE: Passphrase for INPUT_PRIVATE_KEY, unlocked via gpg-agent (optional)
+//   - INPUT_ALLOWED_FINGERPRINTS: Comma-separated fingerprint allowlist for recipient keys (optional)
+//   - INPUT_CONCURRENCY: Max files re-encrypted at once (default: available CPUs)
 //
 // This action:
 // 1. Finds all secret files matching the pattern
 // 2. Collects all GPG public keys (from users + Flux)
-// 3. Re-encrypts each file with all keys
+// 3. Re-encrypts each file's SOPS data key to all of them, natively via
+//    sequoia-openpgp - no `gpg`/`sops` binaries involved. Files whose
+//    recipient fingerprints already match are skipped, so re-runs with an
+//    unchanged key set are a no-op and produce no git diff.
 // 4. Updates .sops.yaml if needed
 
-use anyhow::{Context, Result};
+mod agent;
+mod ephemeral;
+mod pgp;
+mod sops;
+mod sops_yaml;
+mod validate;
+
+use ephemeral::{EphemeralHome, SecretBytes};
+
+use anyhow::{bail, Context, Result};
 use glob::glob;
+use sequoia_openpgp::cert::Cert;
+use sequoia_openpgp::policy::{Policy, StandardPolicy};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
 use std::env;
+use std::fmt::Write as _;
 use std::fs;
 use std::path::PathBuf;
-use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::Semaphore;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct User {
@@ -35,7 +57,7 @@ struct UsersData {
 
 fn find_secret_files(pattern: &str) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
-    
+
     for entry in glob(pattern).context("Failed to read glob pattern")? {
         match entry {
             Ok(path) => {
@@ -48,97 +70,168 @@ fn find_secret_files(pattern: &str) -> Result<Vec<PathBuf>> {
             }
         }
     }
-    
+
     Ok(files)
 }
 
-fn collect_public_keys(users_data: &str, flux_key: &str) -> Result<Vec<String>> {
-    let mut keys = Vec::new();
-    
-    // Parse users data
-    if !users_data.is_empty() {
-        let users: UsersData = serde_json::from_str(users_data)
-            .context("Failed to parse users data")?;
-        
-        for user in users.users {
-            keys.extend(user.gpg_keys_base64);
+/// Decodes, parses, and validates every user's GPG keys plus the Flux key
+/// (see `validate::validate_cert`), returning the certs usable as
+/// re-encryption recipients. Replaces the previous `gpg --import` step -
+/// there is no keyring to pollute, and nothing touches disk.
+///
+/// Fails the run with a combined report if any user who submitted at least
+/// one key ends up with zero usable keys, or if the Flux key itself is
+/// rejected, so a bad or expired key can never silently shrink the
+/// recipient set underneath a maintainer's nose.
+fn load_recipients(
+    users_data: &str,
+    flux_key: &str,
+    allowed_fingerprints: Option<&HashSet<String>>,
+) -> Result<Vec<Cert>> {
+    let policy = StandardPolicy::new();
+    let now = SystemTime::now();
+
+    let users: UsersData = if users_data.is_empty() {
+        UsersData { users: Vec::new() }
+    } else {
+        serde_json::from_str(users_data).context("Failed to parse users data")?
+    };
+
+    let mut recipients = Vec::new();
+    let mut failed_users = Vec::new();
+
+    for user in users.users {
+        let mut usable = 0;
+        let mut reasons = Vec::new();
+
+        for (idx, key_base64) in user.gpg_keys_base64.iter().enumerate() {
+            match decode_and_validate(key_base64, &policy, now, allowed_fingerprints) {
+                Ok(cert) => {
+                    usable += 1;
+                    recipients.push(cert);
+                }
+                Err(reason) => reasons.push(format!("key {idx}: {reason}")),
+            }
+        }
+
+        if usable == 0 && !user.gpg_keys_base64.is_empty() {
+            failed_users.push((user.login, reasons));
         }
     }
-    
-    // Add Flux key
+
     if !flux_key.is_empty() {
-        keys.push(flux_key.to_string());
+        match decode_and_validate(flux_key, &policy, now, allowed_fingerprints) {
+            Ok(cert) => recipients.push(cert),
+            Err(reason) => failed_users.push(("<flux>".to_string(), vec![reason])),
+        }
     }
-    
-    Ok(keys)
-}
 
-fn import_gpg_keys(keys: &[String], gpg_home: &str) -> Result<()> {
-    for (idx, key_base64) in keys.iter().enumerate() {
-        use base64::{Engine as _, engine::general_purpose};
-        let key_bytes = general_purpose::STANDARD
-            .decode(key_base64)
-            .context(format!("Failed to decode GPG key {}", idx))?;
-        let key_str = String::from_utf8(key_bytes)
-            .context(format!("Failed to convert GPG key {} to string", idx))?;
-        
-        // Write key to temporary file
-        let temp_file = format!("{}/key_{}.asc", gpg_home, idx);
-        fs::write(&temp_file, key_str)
-            .context(format!("Failed to write GPG key {}", idx))?;
-        
-        // Import key
-        let output = Command::new("gpg")
-            .env("GNUPGHOME", gpg_home)
-            .arg("--import")
-            .arg("--no-tty")
-            .arg("--batch")
-            .arg(&temp_file)
-            .output()
-            .context(format!("Failed to import GPG key {}", idx))?;
-        
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            eprintln!("Warning: Failed to import GPG key {}: {}", idx, stderr);
+    if !failed_users.is_empty() {
+        let mut report =
+            String::from("The following users contributed zero usable GPG keys:\n");
+        for (login, reasons) in &failed_users {
+            report.push_str(&format!("  - {login}:\n"));
+            for reason in reasons {
+                report.push_str(&format!("      {reason}\n"));
+            }
         }
+        bail!(report);
     }
-    
-    Ok(())
+
+    Ok(recipients)
+}
+
+/// Decodes, parses, and validates a single base64 GPG public key.
+fn decode_and_validate(
+    key_base64: &str,
+    policy: &dyn Policy,
+    now: SystemTime,
+    allowed_fingerprints: Option<&HashSet<String>>,
+) -> Result<Cert, String> {
+    use base64::{engine::general_purpose, Engine as _};
+    let key_bytes = general_purpose::STANDARD
+        .decode(key_base64)
+        .map_err(|e| format!("failed to decode base64: {e}"))?;
+    let cert = pgp::parse_cert(&key_bytes).map_err(|e| format!("failed to parse: {e}"))?;
+    validate::validate_cert(&cert, policy, now, allowed_fingerprints)
+        .map_err(|r| format!("{} ({r})", pgp::fingerprint_hex(&cert)))?;
+    Ok(cert)
+}
+
+/// Outcome of considering a single file for re-encryption.
+enum FileOutcome {
+    /// The file's recipient set already matches and nothing was written.
+    Unchanged,
+    /// The file's recipient set was stale. In dry-run mode it was left
+    /// alone; otherwise it has already been rewritten on disk.
+    Changed,
 }
 
-fn reencrypt_file(file_path: &PathBuf, gpg_home: &str) -> Result<()> {
-    println!("Re-encrypting: {}", file_path.display());
-    
-    // First, try to decrypt to verify it's a valid SOPS file
-    let decrypt_output = Command::new("sops")
-        .env("GNUPGHOME", gpg_home)
-        .arg("-d")
-        .arg(file_path)
-        .output()
-        .context("Failed to decrypt file")?;
-    
-    if !decrypt_output.status.success() {
-        let stderr = String::from_utf8_lossy(&decrypt_output.stderr);
-        anyhow::bail!("Failed to decrypt {}: {}", file_path.display(), stderr);
+fn fingerprint_set(fps: impl IntoIterator<Item = impl AsRef<str>>) -> BTreeSet<String> {
+    fps.into_iter()
+        .map(|fp| fp.as_ref().to_ascii_uppercase())
+        .collect()
+}
+
+type DecryptorPool = Mutex<Vec<Box<dyn sequoia_openpgp::crypto::Decryptor + Send + Sync>>>;
+
+/// Considers one file for re-encryption, writing its log lines into `log`
+/// rather than printing them directly. Several files may be processed
+/// concurrently, so the caller is responsible for flushing each file's log
+/// buffer as a single atomic write once this returns, instead of letting
+/// concurrent calls interleave their output line-by-line.
+fn reencrypt_file(
+    file_path: &PathBuf,
+    own_fingerprint: &sequoia_openpgp::Fingerprint,
+    decryptors: &DecryptorPool,
+    recipients: &[Cert],
+    recipient_fps: &BTreeSet<String>,
+    dry_run: bool,
+    log: &mut String,
+) -> Result<FileOutcome> {
+    let contents = fs::read_to_string(file_path)
+        .with_context(|| format!("Failed to read {}", file_path.display()))?;
+    let mut sops_file = sops::SopsFile::parse(&contents)
+        .with_context(|| format!("{} is not a recognized SOPS file", file_path.display()))?;
+
+    let current_fps = fingerprint_set(sops_file.metadata.pgp.iter().map(|e| &e.fp));
+    if &current_fps == recipient_fps {
+        let _ = writeln!(log, "Unchanged: {}", file_path.display());
+        return Ok(FileOutcome::Unchanged);
     }
-    
-    // Re-encrypt the file
-    // SOPS will use all keys in the keyring
-    let encrypt_output = Command::new("sops")
-        .env("GNUPGHOME", gpg_home)
-        .arg("-e")
-        .arg("-i")  // In-place encryption
-        .arg(file_path)
-        .output()
-        .context("Failed to re-encrypt file")?;
-    
-    if !encrypt_output.status.success() {
-        let stderr = String::from_utf8_lossy(&encrypt_output.stderr);
-        anyhow::bail!("Failed to re-encrypt {}: {}", file_path.display(), stderr);
+
+    if dry_run {
+        let _ = writeln!(log, "Would re-encrypt: {}", file_path.display());
+        return Ok(FileOutcome::Changed);
     }
-    
-    println!("✅ Successfully re-encrypted: {}", file_path.display());
-    Ok(())
+
+    let _ = writeln!(log, "Re-encrypting: {}", file_path.display());
+
+    let data_key = {
+        let mut decryptors = decryptors.lock().expect("decryptor pool lock poisoned");
+        pgp::decrypt_data_key(own_fingerprint, &mut decryptors, &sops_file.metadata.pgp)
+            .with_context(|| format!("Failed to recover data key for {}", file_path.display()))?
+    };
+
+    let reencrypted = pgp::encrypt_data_key_for(recipients, &data_key)
+        .with_context(|| format!("Failed to re-encrypt data key for {}", file_path.display()))?;
+
+    let now = chrono::Utc::now().to_rfc3339();
+    sops_file.metadata.pgp = reencrypted
+        .into_iter()
+        .map(|(enc, fp)| sops::PgpEntry {
+            created_at: now.clone(),
+            enc,
+            fp,
+        })
+        .collect();
+    sops_file.metadata.lastmodified = now;
+
+    fs::write(file_path, sops_file.serialize())
+        .with_context(|| format!("Failed to write {}", file_path.display()))?;
+
+    let _ = writeln!(log, "✅ Successfully re-encrypted: {}", file_path.display());
+    Ok(FileOutcome::Changed)
 }
 
 #[tokio::main]
@@ -146,89 +239,180 @@ async fn main() -> Result<()> {
     // Read inputs
     let private_key = env::var("INPUT_PRIVATE_KEY")
         .context("INPUT_PRIVATE_KEY environment variable required")?;
-    let public_keys_json = env::var("INPUT_PUBLIC_KEYS").unwrap_or_else(|_| "{\"users\":[]}".to_string());
+    let public_keys_json =
+        env::var("INPUT_PUBLIC_KEYS").unwrap_or_else(|_| "{\"users\":[]}".to_string());
     let flux_key = env::var("INPUT_FLUX_KEY").unwrap_or_else(|_| String::new());
     let secrets_pattern = env::var("INPUT_SECRETS_PATTERN")
         .unwrap_or_else(|_| "**/application.secrets.env".to_string());
-    let sops_version = env::var("INPUT_SOPS_VERSION")
-        .unwrap_or_else(|_| "3.10.2".to_string());
-    
-    // Get GPG home from environment or use default
-    let gpg_home = env::var("GNUPGHOME")
-        .unwrap_or_else(|_| format!("{}/.gnupg", env::var("HOME").unwrap_or_else(|_| "/tmp".to_string())));
-    
-    // Create GPG home directory
-    fs::create_dir_all(&gpg_home)
-        .context("Failed to create GPG home directory")?;
-    
-    // Import private key
-    println!("Importing GPG private key...");
-    use base64::{Engine as _, engine::general_purpose};
-    let private_key_bytes = general_purpose::STANDARD
-        .decode(&private_key)
-        .context("Failed to decode private key")?;
-    let private_key_str = String::from_utf8(private_key_bytes)
-        .context("Failed to convert private key to string")?;
-    
-    let temp_private = format!("{}/private_key.asc", gpg_home);
-    fs::write(&temp_private, private_key_str)
-        .context("Failed to write private key")?;
-    
-    let import_output = Command::new("gpg")
-        .env("GNUPGHOME", &gpg_home)
-        .arg("--import")
-        .arg("--no-tty")
-        .arg("--batch")
-        .arg(&temp_private)
-        .output()
-        .context("Failed to import private key")?;
-    
-    if !import_output.status.success() {
-        let stderr = String::from_utf8_lossy(&import_output.stderr);
-        anyhow::bail!("Failed to import private key: {}", stderr);
-    }
-    
-    // Collect public keys
+    let ephemeral = env::var("INPUT_EPHEMERAL")
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(true);
+    let dry_run = env::var("INPUT_DRY_RUN")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    let key_passphrase = env::var("INPUT_KEY_PASSPHRASE").ok().filter(|p| !p.is_empty());
+    let concurrency: usize = env::var("INPUT_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        });
+    let allowed_fingerprints: Option<HashSet<String>> = env::var("INPUT_ALLOWED_FINGERPRINTS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            v.split(',')
+                .map(|fp| fp.trim().to_ascii_uppercase())
+                .collect()
+        });
+
+    let gpg_home = EphemeralHome::new(ephemeral)?;
+    println!(
+        "Using {} GPG home: {}",
+        if ephemeral { "ephemeral" } else { "persistent" },
+        gpg_home.path().display()
+    );
+
+    // Parse our own private key
+    println!("Loading GPG private key...");
+    use base64::{engine::general_purpose, Engine as _};
+    let private_key_bytes = SecretBytes::new(
+        general_purpose::STANDARD
+            .decode(&private_key)
+            .context("Failed to decode private key")?,
+    );
+    let own_cert =
+        pgp::parse_cert(private_key_bytes.as_slice()).context("Failed to parse private key")?;
+    drop(private_key_bytes);
+    let own_fingerprint = own_cert.fingerprint();
+
+    // Unlock the secret key either directly (unprotected key material) or
+    // through gpg-agent, if a passphrase was supplied.
+    let decryptors = match &key_passphrase {
+        Some(passphrase) => {
+            agent::install_loopback_pinentry(gpg_home.path(), passphrase)
+                .context("Failed to configure non-interactive pinentry")?;
+            agent::agent_decryptors(gpg_home.path(), &own_cert)
+                .await
+                .context("Failed to unlock private key via gpg-agent")?
+        }
+        None => pgp::direct_decryptors(&own_cert),
+    };
+
+    // Collect and validate public keys
     println!("Collecting public keys...");
-    let public_keys = collect_public_keys(&public_keys_json, &flux_key)?;
-    println!("Found {} public keys", public_keys.len());
-    
-    // Import public keys
-    if !public_keys.is_empty() {
-        import_gpg_keys(&public_keys, &gpg_home)?;
+    let recipients = load_recipients(
+        &public_keys_json,
+        &flux_key,
+        allowed_fingerprints.as_ref(),
+    )?;
+    if recipients.is_empty() {
+        bail!(
+            "Refusing to re-encrypt: no usable recipients (no public keys and no Flux key) - \
+             writing files with an empty recipient list would lock everyone out of them"
+        );
     }
-    
+    println!("Found {} usable public key(s)", recipients.len());
+    let recipient_fps = fingerprint_set(recipients.iter().map(pgp::fingerprint_hex));
+
+    // Keep .sops.yaml's creation_rules in sync with this recipient set so
+    // `sops` run locally uses the same keys this action enforces.
+    if !dry_run {
+        let path_regex = sops_yaml::glob_to_path_regex(&secrets_pattern);
+        let fingerprints: Vec<String> = recipients.iter().map(pgp::fingerprint_hex).collect();
+        sops_yaml::reconcile(std::path::Path::new(".sops.yaml"), &path_regex, &fingerprints)
+            .context("Failed to reconcile .sops.yaml")?;
+        println!("Updated .sops.yaml creation_rules for pattern: {}", secrets_pattern);
+    }
+
     // Find secret files
     println!("Finding secret files matching pattern: {}", secrets_pattern);
     let secret_files = find_secret_files(&secrets_pattern)?;
     println!("Found {} secret file(s)", secret_files.len());
-    
+
     if secret_files.is_empty() {
         println!("No secret files found matching pattern: {}", secrets_pattern);
         return Ok(());
     }
-    
-    // Re-encrypt each file
-    let mut success_count = 0;
-    let mut error_count = 0;
-    
+
+    // Re-encrypt each file, bounded to `concurrency` in flight at once.
+    // Each file is independent once keys are loaded; the decryptor pool
+    // is the only thing shared, and it's guarded by a mutex.
+    println!("Re-encrypting with up to {} file(s) in flight", concurrency);
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let decryptors = Arc::new(Mutex::new(decryptors));
+    let recipients = Arc::new(recipients);
+    let recipient_fps = Arc::new(recipient_fps);
+    let own_fingerprint = Arc::new(own_fingerprint);
+
+    let mut tasks = Vec::with_capacity(secret_files.len());
     for file in secret_files {
-        match reencrypt_file(&file, &gpg_home) {
-            Ok(_) => success_count += 1,
+        let semaphore = semaphore.clone();
+        let decryptors = decryptors.clone();
+        let recipients = recipients.clone();
+        let recipient_fps = recipient_fps.clone();
+        let own_fingerprint = own_fingerprint.clone();
+
+        tasks.push(tokio::spawn(async move {
+            let _permit = semaphore
+                .acquire_owned()
+                .await
+                .expect("semaphore should not be closed");
+            tokio::task::spawn_blocking(move || {
+                let mut log = String::new();
+                let result = reencrypt_file(
+                    &file,
+                    &own_fingerprint,
+                    &decryptors,
+                    &recipients,
+                    &recipient_fps,
+                    dry_run,
+                    &mut log,
+                );
+                (file, log, result)
+            })
+            .await
+            .expect("re-encryption task panicked")
+        }));
+    }
+
+    let mut changed_count = 0;
+    let mut unchanged_count = 0;
+    let mut error_count = 0;
+
+    for task in tasks {
+        let (file, log, result) = task.await.expect("re-encryption task panicked");
+        print!("{log}");
+        match result {
+            Ok(FileOutcome::Changed) => changed_count += 1,
+            Ok(FileOutcome::Unchanged) => unchanged_count += 1,
             Err(e) => {
                 eprintln!("Error re-encrypting {}: {}", file.display(), e);
                 error_count += 1;
             }
         }
     }
-    
+
     println!("\nRe-encryption complete:");
-    println!("  ✅ Success: {}", success_count);
+    println!(
+        "  ✅ Changed: {}  ⏭️  Unchanged: {}",
+        changed_count, unchanged_count
+    );
     if error_count > 0 {
         println!("  ❌ Errors: {}", error_count);
         std::process::exit(1);
     }
-    
+
+    if dry_run && changed_count > 0 {
+        println!(
+            "  🔍 Dry run: {} file(s) have a stale recipient set",
+            changed_count
+        );
+        std::process::exit(1);
+    }
+
     Ok(())
 }
-
@@ -0,0 +1,205 @@
+// Pure-Rust OpenPGP operations backing the SOPS re-keying flow, using
+// `sequoia-openpgp` in place of shelling out to `gpg`/`sops`.
+
+use crate::sops::PgpEntry;
+use anyhow::{anyhow, Context, Result};
+use sequoia_openpgp as openpgp;
+use openpgp::cert::Cert;
+use openpgp::crypto::{Decryptor, SessionKey};
+use openpgp::packet::{PKESK, SKESK};
+use openpgp::parse::{
+    stream::{DecryptionHelper, DecryptorBuilder, MessageStructure, VerificationHelper},
+    Parse,
+};
+use openpgp::policy::StandardPolicy;
+use openpgp::serialize::stream::{Armorer, Encryptor2, LiteralWriter, Message, Recipient};
+use openpgp::types::SymmetricAlgorithm;
+use openpgp::{Fingerprint, KeyHandle};
+use std::io::{Read, Write};
+
+pub fn parse_cert(armored: &[u8]) -> Result<Cert> {
+    Cert::from_bytes(armored).context("failed to parse OpenPGP certificate")
+}
+
+pub fn fingerprint_hex(cert: &Cert) -> String {
+    cert.fingerprint().to_hex()
+}
+
+/// Builds a `Decryptor` for every encryption-capable secret subkey of
+/// `cert`, assuming the secret key material is unprotected. Use
+/// `agent::agent_decryptors` instead when the key is passphrase-protected.
+pub fn direct_decryptors(cert: &Cert) -> Vec<Box<dyn Decryptor + Send + Sync>> {
+    let policy = StandardPolicy::new();
+    cert.keys()
+        .with_policy(&policy, None)
+        .secret()
+        .for_transport_encryption()
+        .for_storage_encryption()
+        .filter_map(|ka| ka.key().clone().into_keypair().ok())
+        .map(|kp| Box::new(kp) as Box<dyn Decryptor + Send + Sync>)
+        .collect()
+}
+
+/// Recovers the 32-byte SOPS data key from whichever `pgp` entry was
+/// encrypted to `own_fingerprint`, trying each of `decryptors` in turn.
+/// `decryptors` may be backed directly by in-memory secret key material or
+/// by a running `gpg-agent` - both implement the same `Decryptor` trait.
+/// Takes them by `&mut` rather than by value so the same decryptors can be
+/// reused across every file in a batch.
+pub fn decrypt_data_key(
+    own_fingerprint: &Fingerprint,
+    decryptors: &mut [Box<dyn Decryptor + Send + Sync>],
+    entries: &[PgpEntry],
+) -> Result<Vec<u8>> {
+    let policy = StandardPolicy::new();
+
+    let entry = entries
+        .iter()
+        .find(|e| e.fp.eq_ignore_ascii_case(&own_fingerprint.to_hex()))
+        .ok_or_else(|| anyhow!("no sops.pgp entry matches our fingerprint {own_fingerprint}"))?;
+
+    let helper = Helper { decryptors };
+    let mut decryptor = DecryptorBuilder::from_bytes(entry.enc.as_bytes())
+        .context("failed to read armored data-key message")?
+        .with_policy(&policy, None, helper)
+        .context("failed to decrypt data key")?;
+
+    let mut data_key = Vec::new();
+    decryptor
+        .read_to_end(&mut data_key)
+        .context("failed to read decrypted data key")?;
+    Ok(data_key)
+}
+
+/// Re-encrypts `data_key` to every recipient cert, returning the new
+/// `(armored enc, fingerprint)` pairs in the same order as `recipients`.
+pub fn encrypt_data_key_for(recipients: &[Cert], data_key: &[u8]) -> Result<Vec<(String, String)>> {
+    let policy = StandardPolicy::new();
+    let mut out = Vec::with_capacity(recipients.len());
+
+    for cert in recipients {
+        let recipient_keys: Vec<_> = cert
+            .keys()
+            .with_policy(&policy, None)
+            .alive()
+            .revoked(false)
+            .for_transport_encryption()
+            .for_storage_encryption()
+            .collect();
+        if recipient_keys.is_empty() {
+            return Err(anyhow!(
+                "cert {} has no usable encryption-capable subkey",
+                cert.fingerprint()
+            ));
+        }
+
+        let mut armored = Vec::new();
+        {
+            let message = Message::new(&mut armored);
+            let message = Armorer::new(message).build()?;
+            let message = Encryptor2::for_recipients(
+                message,
+                recipient_keys
+                    .iter()
+                    .map(|ka| Recipient::from(ka.key())),
+            )
+            .symmetric_algo(SymmetricAlgorithm::AES256)
+            .build()
+            .context("failed to build OpenPGP encryptor")?;
+            let mut message = LiteralWriter::new(message).build()?;
+            message.write_all(data_key)?;
+            message.finalize()?;
+        }
+
+        out.push((
+            String::from_utf8(armored).context("encryptor produced non-UTF8 armor")?,
+            cert.fingerprint().to_hex(),
+        ));
+    }
+
+    Ok(out)
+}
+
+struct Helper<'a> {
+    decryptors: &'a mut [Box<dyn Decryptor + Send + Sync>],
+}
+
+impl<'a> VerificationHelper for Helper<'a> {
+    fn get_certs(&mut self, _ids: &[KeyHandle]) -> openpgp::Result<Vec<Cert>> {
+        // The data key blob is encrypted-only, never signed - nothing to
+        // fetch certs for.
+        Ok(Vec::new())
+    }
+
+    fn check(&mut self, _structure: MessageStructure) -> openpgp::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> DecryptionHelper for Helper<'a> {
+    fn decrypt<D>(
+        &mut self,
+        pkesks: &[PKESK],
+        _skesks: &[SKESK],
+        sym_algo: Option<SymmetricAlgorithm>,
+        mut decrypt: D,
+    ) -> openpgp::Result<Option<Fingerprint>>
+    where
+        D: FnMut(SymmetricAlgorithm, &SessionKey) -> bool,
+    {
+        for pkesk in pkesks {
+            for decryptor in self.decryptors.iter_mut() {
+                if let Some((algo, session_key)) = pkesk.decrypt(decryptor.as_mut(), sym_algo) {
+                    if decrypt(algo, &session_key) {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+
+        Err(anyhow!("no secret key could decrypt the SOPS data key"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sops::PgpEntry;
+    use sequoia_openpgp::cert::CertBuilder;
+
+    #[test]
+    fn encrypt_then_decrypt_data_key_round_trips() -> Result<()> {
+        let (cert, _revocation) = CertBuilder::general_purpose(None, Some("Test <test@example.com>"))
+            .generate()?;
+
+        let data_key = b"0123456789abcdef0123456789abcdef".to_vec();
+        let reencrypted = encrypt_data_key_for(std::slice::from_ref(&cert), &data_key)?;
+        assert_eq!(reencrypted.len(), 1);
+        let (enc, fp) = &reencrypted[0];
+        assert_eq!(*fp, fingerprint_hex(&cert));
+
+        let entries = vec![PgpEntry {
+            created_at: "2026-01-01T00:00:00Z".to_string(),
+            enc: enc.clone(),
+            fp: fp.clone(),
+        }];
+
+        let mut decryptors = direct_decryptors(&cert);
+        let own_fingerprint = cert.fingerprint();
+        let decrypted = decrypt_data_key(&own_fingerprint, &mut decryptors, &entries)?;
+
+        assert_eq!(decrypted, data_key);
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_data_key_fails_without_matching_fingerprint() {
+        let entries: Vec<PgpEntry> = Vec::new();
+        let (cert, _revocation) = CertBuilder::general_purpose(None, Some("Test <test@example.com>"))
+            .generate()
+            .unwrap();
+        let mut decryptors = direct_decryptors(&cert);
+        let own_fingerprint = cert.fingerprint();
+        assert!(decrypt_data_key(&own_fingerprint, &mut decryptors, &entries).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+// Parsing and serialization for the SOPS dotenv-style secrets format.
+//
+// A SOPS-encrypted `.env` file stores the encrypted values as ordinary
+// `KEY=value` lines (values wrapped in `ENC[...]`) followed by a block of
+// `sops_*` metadata lines. Each recipient backend (`pgp`, `kms`, `age`, ...)
+// is flattened into a single `sops_<backend>=<json array>` line, e.g.
+// `sops_pgp=[{"created_at":"...","enc":"-----BEGIN PGP MESSAGE-----\n...","fp":"..."}]`,
+// and `sops_lastmodified`/`sops_mac` carry their values unquoted. We only
+// rewrite the `pgp` recipient list plus the top-level
+// `lastmodified`/`mac`/`version` fields; every other `sops_*` line (a
+// second backend like `kms`/`age`, or a field we don't know about) is kept
+// verbatim so a recipient this action doesn't manage is never silently
+// dropped from the file.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PgpEntry {
+    pub created_at: String,
+    pub enc: String,
+    pub fp: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SopsMetadata {
+    pub pgp: Vec<PgpEntry>,
+    pub lastmodified: String,
+    pub mac: String,
+    pub version: String,
+    /// Every other top-level `sops_*` line (`sops_kms`, `sops_age`,
+    /// `sops_azure_kv`, `sops_unencrypted_suffix`, ...), kept as its raw
+    /// `key=value` text and in original order. We don't manage these, so
+    /// they pass through unexamined rather than being dropped.
+    other: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SopsFile {
+    /// Non-metadata lines, kept verbatim and in their original order.
+    pub data_lines: Vec<String>,
+    pub metadata: SopsMetadata,
+}
+
+impl SopsFile {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut data_lines = Vec::new();
+        let mut pgp: Option<Vec<PgpEntry>> = None;
+        let mut lastmodified = String::new();
+        let mut mac = String::new();
+        let mut version = String::new();
+        let mut other = Vec::new();
+
+        for line in contents.lines() {
+            if let Some(value) = line.strip_prefix("sops_pgp=") {
+                pgp = Some(serde_json::from_str(value).context("malformed sops_pgp metadata")?);
+            } else if let Some(value) = line.strip_prefix("sops_lastmodified=") {
+                lastmodified = value.to_string();
+            } else if let Some(value) = line.strip_prefix("sops_mac=") {
+                mac = value.to_string();
+            } else if let Some(value) = line.strip_prefix("sops_version=") {
+                version = value.to_string();
+            } else if line.starts_with("sops_") {
+                // A second recipient backend (kms/azure_kv/gcp_kms/age/...)
+                // or a field we don't recognize: not ours to manage, so
+                // keep the line exactly as written instead of dropping it.
+                other.push(line.to_string());
+            } else if !line.is_empty() {
+                data_lines.push(line.to_string());
+            }
+        }
+
+        let pgp = pgp
+            .filter(|entries| !entries.is_empty())
+            .context("no sops_pgp entries found - not a PGP-encrypted SOPS file")?;
+
+        Ok(SopsFile {
+            data_lines,
+            metadata: SopsMetadata {
+                pgp,
+                lastmodified,
+                mac,
+                version,
+                other,
+            },
+        })
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = String::new();
+        for line in &self.data_lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+        for line in &self.metadata.other {
+            out.push_str(line);
+            out.push('\n');
+        }
+        let pgp_json = serde_json::to_string(&self.metadata.pgp)
+            .expect("Vec<PgpEntry> of plain strings always serializes");
+        out.push_str(&format!("sops_pgp={pgp_json}\n"));
+        out.push_str(&format!(
+            "sops_lastmodified={}\n",
+            self.metadata.lastmodified
+        ));
+        out.push_str(&format!("sops_mac={}\n", self.metadata.mac));
+        out.push_str(&format!("sops_version={}\n", self.metadata.version));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "FOO_SECRET=ENC[AES256_GCM,data:abcd==,iv:abcd==,tag:abcd==,type:str]\n\
+sops_kms=[]\n\
+sops_gcp_kms=[]\n\
+sops_azure_kv=[]\n\
+sops_age=[]\n\
+sops_lastmodified=2026-01-01T00:00:00Z\n\
+sops_mac=ENC[AES256_GCM,data:abcd==,iv:abcd==,tag:abcd==,type:str]\n\
+sops_pgp=[{\"created_at\":\"2026-01-01T00:00:00Z\",\"enc\":\"-----BEGIN PGP MESSAGE-----\\nhF4D...\\n-----END PGP MESSAGE-----\\n\",\"fp\":\"0123456789ABCDEF0123456789ABCDEF01234567\"}]\n\
+sops_unencrypted_suffix=_unencrypted\n\
+sops_version=3.8.1\n";
+
+    #[test]
+    fn parse_then_serialize_round_trips_pgp_metadata() {
+        let parsed = SopsFile::parse(SAMPLE).expect("sample should parse");
+        assert_eq!(
+            parsed.data_lines,
+            vec!["FOO_SECRET=ENC[AES256_GCM,data:abcd==,iv:abcd==,tag:abcd==,type:str]".to_string()]
+        );
+        assert_eq!(parsed.metadata.pgp.len(), 1);
+        assert_eq!(
+            parsed.metadata.pgp[0].fp,
+            "0123456789ABCDEF0123456789ABCDEF01234567"
+        );
+        assert_eq!(
+            parsed.metadata.pgp[0].enc,
+            "-----BEGIN PGP MESSAGE-----\nhF4D...\n-----END PGP MESSAGE-----\n"
+        );
+        assert_eq!(parsed.metadata.lastmodified, "2026-01-01T00:00:00Z");
+        assert_eq!(
+            parsed.metadata.mac,
+            "ENC[AES256_GCM,data:abcd==,iv:abcd==,tag:abcd==,type:str]"
+        );
+        assert_eq!(parsed.metadata.version, "3.8.1");
+
+        let reserialized = parsed.serialize();
+        assert!(reserialized.contains("sops_pgp=[{"));
+        assert!(reserialized.contains("sops_lastmodified=2026-01-01T00:00:00Z"));
+        // Backends this action doesn't manage must survive untouched.
+        assert!(reserialized.contains("sops_kms=[]"));
+        assert!(reserialized.contains("sops_age=[]"));
+        assert!(reserialized.contains("sops_unencrypted_suffix=_unencrypted"));
+
+        let reparsed = SopsFile::parse(&reserialized).expect("reserialized output should parse");
+        assert_eq!(reparsed.data_lines, parsed.data_lines);
+        assert_eq!(reparsed.metadata.pgp.len(), parsed.metadata.pgp.len());
+        assert_eq!(reparsed.metadata.pgp[0].fp, parsed.metadata.pgp[0].fp);
+        assert_eq!(reparsed.metadata.pgp[0].enc, parsed.metadata.pgp[0].enc);
+        assert_eq!(reparsed.metadata.lastmodified, parsed.metadata.lastmodified);
+        assert_eq!(reparsed.metadata.mac, parsed.metadata.mac);
+        assert_eq!(reparsed.metadata.version, parsed.metadata.version);
+        assert_eq!(reparsed.metadata.other, parsed.metadata.other);
+    }
+
+    #[test]
+    fn parse_rejects_file_with_no_pgp_entries() {
+        assert!(SopsFile::parse("FOO=ENC[...]\nsops_version=3.8.1\n").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_file_with_empty_pgp_list() {
+        assert!(SopsFile::parse("FOO=ENC[...]\nsops_pgp=[]\nsops_version=3.8.1\n").is_err());
+    }
+}
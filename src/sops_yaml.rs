@@ -0,0 +1,102 @@
+// Keeps `.sops.yaml` in sync with the recipient set this action enforces,
+// so `sops` run locally against the same files uses the same keys.
+
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+use std::path::Path;
+
+/// Converts our glob-style `INPUT_SECRETS_PATTERN` into the regex SOPS
+/// expects for a creation rule's `path_regex` (matched anywhere in the
+/// relative file path, not anchored at the start).
+pub fn glob_to_path_regex(pattern: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    // `**/` matches any number of directories, including none.
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                } else {
+                    regex.push_str("[^/]*");
+                }
+            }
+            '.' | '+' | '(' | ')' | '[' | ']' | '{' | '}' | '^' | '$' | '|' | '\\' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            other => regex.push(other),
+        }
+    }
+
+    format!("{regex}$")
+}
+
+/// Reads `path` (if it exists), updates or inserts the `creation_rules`
+/// entry matching `path_regex` so its `pgp` field lists `fingerprints`
+/// (comma-joined), and writes the result back. Any other rules, and any
+/// other top-level keys, are preserved verbatim.
+pub fn reconcile(path: &Path, path_regex: &str, fingerprints: &[String]) -> Result<()> {
+    let existing = if path.exists() {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?
+    } else {
+        String::new()
+    };
+
+    let mut doc: Value = if existing.trim().is_empty() {
+        Value::Mapping(Mapping::new())
+    } else {
+        serde_yaml::from_str(&existing)
+            .with_context(|| format!("failed to parse {}", path.display()))?
+    };
+
+    let root = doc
+        .as_mapping_mut()
+        .context("top level of .sops.yaml must be a mapping")?;
+
+    let rules = root
+        .entry(Value::String("creation_rules".to_string()))
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    let rules = rules
+        .as_sequence_mut()
+        .context("creation_rules must be a sequence")?;
+
+    let pgp_value = Value::String(fingerprints.join(","));
+    let matching_rule = rules.iter_mut().find(|rule| {
+        rule.as_mapping()
+            .and_then(|m| m.get(Value::String("path_regex".to_string())))
+            .and_then(|v| v.as_str())
+            == Some(path_regex)
+    });
+
+    match matching_rule {
+        Some(rule) => {
+            rule.as_mapping_mut()
+                .context("creation_rules entries must be mappings")?
+                .insert(Value::String("pgp".to_string()), pgp_value);
+        }
+        None => {
+            let mut rule = Mapping::new();
+            rule.insert(
+                Value::String("path_regex".to_string()),
+                Value::String(path_regex.to_string()),
+            );
+            rule.insert(Value::String("pgp".to_string()), pgp_value);
+            rules.push(Value::Mapping(rule));
+        }
+    }
+
+    let serialized = serde_yaml::to_string(&doc).context("failed to serialize .sops.yaml")?;
+    std::fs::write(path, serialized)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    Ok(())
+}
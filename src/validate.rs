@@ -0,0 +1,69 @@
+// Validates recipient GPG keys before they're trusted to receive
+// re-encrypted SOPS data keys: a key must carry a valid self-signature and
+// a live (non-expired, non-revoked) encryption-capable subkey, and - if an
+// allowlist is configured - its fingerprint must be on it.
+
+use sequoia_openpgp::cert::Cert;
+use sequoia_openpgp::policy::Policy;
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+/// Why a candidate recipient key was rejected.
+#[derive(Debug)]
+pub enum Rejection {
+    InvalidSelfSignature(String),
+    NoLiveEncryptionSubkey,
+    NotAllowlisted,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::InvalidSelfSignature(reason) => {
+                write!(f, "invalid or missing self-signature ({reason})")
+            }
+            Rejection::NoLiveEncryptionSubkey => {
+                write!(f, "no non-expired, non-revoked encryption-capable subkey")
+            }
+            Rejection::NotAllowlisted => write!(f, "fingerprint not in INPUT_ALLOWED_FINGERPRINTS"),
+        }
+    }
+}
+
+/// Checks `cert` against `policy` as of `now`, rejecting it unless it has a
+/// valid self-signature, at least one live encryption-capable subkey, and
+/// (when `allowlist` is `Some`) an allowlisted fingerprint.
+pub fn validate_cert(
+    cert: &Cert,
+    policy: &dyn Policy,
+    now: SystemTime,
+    allowlist: Option<&HashSet<String>>,
+) -> Result<(), Rejection> {
+    let valid_cert = cert
+        .with_policy(policy, now)
+        .map_err(|e| Rejection::InvalidSelfSignature(e.to_string()))?;
+
+    let has_live_subkey = valid_cert
+        .keys()
+        .alive()
+        .revoked(false)
+        .for_transport_encryption()
+        .for_storage_encryption()
+        .next()
+        .is_some();
+    if !has_live_subkey {
+        return Err(Rejection::NoLiveEncryptionSubkey);
+    }
+
+    if let Some(allowlist) = allowlist {
+        let fingerprint = cert.fingerprint().to_hex();
+        if !allowlist
+            .iter()
+            .any(|fp| fp.eq_ignore_ascii_case(&fingerprint))
+        {
+            return Err(Rejection::NotAllowlisted);
+        }
+    }
+
+    Ok(())
+}